@@ -1,21 +1,166 @@
 #![windows_subsystem = "windows"]
 
 extern crate java_locator;
+extern crate jni;
 extern crate msgbox;
+extern crate serde;
+extern crate serde_json;
 
 use std::env;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use jni::{InitArgsBuilder, JNIVersion, JavaVM};
 use msgbox::IconType;
-use std::ops::Add;
+use serde::Deserialize;
+
+/// Hints to NVIDIA Optimus drivers that they should run this process on the
+/// discrete GPU rather than the integrated one, on hybrid-GPU laptops.
+/// The driver looks these symbols up by name in the executable, so they
+/// must stay exported with exactly this name and not be optimized away.
+#[cfg(target_os = "windows")]
+#[no_mangle]
+#[used]
+pub static NvOptimusEnablement: u32 = 1;
+
+/// Same purpose as `NvOptimusEnablement`, but for AMD's PowerXpress driver.
+#[cfg(target_os = "windows")]
+#[no_mangle]
+#[used]
+pub static AmdPowerXpressRequestHighPerformance: u32 = 1;
+
+/// `#[no_mangle]`/`#[used]` on the statics above only stop them from being
+/// dead-code-eliminated; an `.exe` has no export table by default, so the
+/// driver (which scans the export table, same as it would for a DLL)
+/// still wouldn't see them. MSVC's `link.exe` additionally reads `.drectve`
+/// sections in each object file as extra command-line arguments, so
+/// embedding `/EXPORT:` directives here is what actually puts the two
+/// symbols into the executable's export table.
+#[cfg(target_os = "windows")]
+#[used]
+#[link_section = ".drectve"]
+pub static GPU_HINT_EXPORTS: [u8; 73] =
+    *b"/EXPORT:NvOptimusEnablement /EXPORT:AmdPowerXpressRequestHighPerformance ";
+
+/// Launch settings normally inferred from defaults, but overridable via an
+/// optional `jgnash.json` dropped next to the executable.
+#[derive(Deserialize)]
+struct LaunchConfig {
+    #[serde(default = "LaunchConfig::default_main_class")]
+    main_class: String,
+    #[serde(default = "LaunchConfig::default_classpath")]
+    classpath: Vec<String>,
+    #[serde(default)]
+    vm_args: Vec<String>,
+    /// Set by `apply_modular_jdk_args`, never read from `jgnash.json`: an
+    /// `@argfile` of module flags to apply when running on a modular JDK.
+    #[serde(skip)]
+    modular_args_file: Option<PathBuf>,
+}
+
+impl LaunchConfig {
+    fn default_main_class() -> String {
+        "jGnash".to_string()
+    }
+
+    fn default_classpath() -> Vec<String> {
+        vec!["lib/*".to_string()]
+    }
+}
+
+impl Default for LaunchConfig {
+    fn default() -> Self {
+        LaunchConfig {
+            main_class: LaunchConfig::default_main_class(),
+            classpath: LaunchConfig::default_classpath(),
+            vm_args: Vec::new(),
+            modular_args_file: None,
+        }
+    }
+}
+
+/// Reads `jgnash.json` next to the executable, if present, falling back to
+/// `LaunchConfig::default()` when it's missing or fails to parse.
+fn load_launch_config() -> LaunchConfig {
+    let config_path = get_execution_path().join("jgnash.json");
+
+    fs::read_to_string(&config_path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Joins classpath entries with the platform's classpath separator,
+/// resolving each entry relative to the executable's directory. A trailing
+/// `dir/*` entry is expanded into the actual `*.jar` files in `dir`: that
+/// wildcard form is only understood by the `java`/`javaw` launcher, not by
+/// `java.class.path` when set directly through the JNI Invocation API, so
+/// we expand it ourselves to keep both launch paths working identically.
+fn build_classpath(config: &LaunchConfig) -> String {
+    build_classpath_under(config, &get_execution_path())
+}
+
+/// Does the work for `build_classpath`, resolving entries against `base`
+/// rather than always against the executable's directory, so the wildcard
+/// expansion below can be exercised against a temp directory in tests.
+fn build_classpath_under(config: &LaunchConfig, base: &Path) -> String {
+    let separator = if cfg!(target_family = "windows") {
+        ";"
+    } else {
+        ":"
+    };
+
+    config
+        .classpath
+        .iter()
+        .flat_map(|entry| expand_classpath_entry(entry, base))
+        .collect::<Vec<String>>()
+        .join(separator)
+}
+
+/// Expands a single classpath entry relative to `base`. If it's a `dir/*`
+/// wildcard, returns every `*.jar` file found directly inside `dir` (not
+/// recursively, matching `java`'s own semantics); otherwise returns the
+/// entry resolved as-is.
+fn expand_classpath_entry(entry: &str, base: &Path) -> Vec<String> {
+    match entry
+        .strip_suffix("/*")
+        .or_else(|| entry.strip_suffix("\\*"))
+    {
+        Some(dir) => {
+            let jar_dir = base.join(dir);
+
+            let mut jars: Vec<String> = fs::read_dir(&jar_dir)
+                .into_iter()
+                .flatten()
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.extension().map_or(false, |ext| ext == "jar"))
+                .filter_map(|p| p.as_os_str().to_str().map(|s| s.to_string()))
+                .collect();
+            jars.sort();
+            jars
+        }
+        None => vec![base.join(entry).as_os_str().to_str().unwrap().to_string()],
+    }
+}
 
 fn main() {
-    let java_home = java_locator::locate_java_home();
+    let java_home = locate_bundled_runtime()
+        .or_else(|| java_locator::locate_java_home().ok())
+        .or_else(locate_java_on_path);
+    let mut config = load_launch_config();
+
+    if let Some(ref home) = java_home {
+        apply_modular_jdk_args(home, &mut config);
+    }
+
+    let program_args = parse_argv(env::args().skip(1).collect(), &mut config);
 
     match java_home {
-        Ok(s) => launch_jgnash(s),
-        Err(_e) => msgbox::create(
+        Some(s) => launch_jgnash(s, config, program_args),
+        None => msgbox::create(
             "Error",
             "Unable to locate a valid Java installation.\n\n\
              Please download a JVM from https://adoptopenjdk.net.",
@@ -24,53 +169,452 @@ fn main() {
     }
 }
 
-#[cfg(target_family = "windows")]
-fn launch_jgnash(s: String) {
-    // java executable
-    let java_exe = s.add("\\bin\\javaw.exe");
+/// Looks for a minimized `jlink` runtime image bundled next to the
+/// executable (`<exe_dir>/runtime`) so jGnash can ship self-contained and
+/// doesn't depend on whatever JVM, if any, the user has installed.
+fn locate_bundled_runtime() -> Option<String> {
+    let runtime_dir = get_execution_path().join("runtime");
 
-    //let class_path = "c:\\temp\\jGnash-3.4.0\\lib\\*";
+    if runtime_dir.join("bin").join("server").exists()
+        || runtime_dir.join("lib").join("server").exists()
+    {
+        runtime_dir.as_os_str().to_str().map(|s| s.to_string())
+    } else {
+        None
+    }
+}
 
-    let class_path = get_execution_path()
-        .as_os_str()
-        .to_str()
-        .unwrap()
-        .to_string()
-        .add("\\lib\\*");
+/// JRuby-launcher-style argument handling: `-J<opt>` is stripped and
+/// forwarded as a raw VM option, `-X`/`-Xhelp` prints usage and exits, and
+/// everything else is returned to be forwarded on to `jGnash.main`.
+fn parse_argv(args: Vec<String>, config: &mut LaunchConfig) -> Vec<String> {
+    let mut program_args = Vec::new();
 
-    Command::new(&java_exe)
-        .arg("-classpath")
-        .arg(&class_path)
-        .arg("jGnash")
-        .spawn()
-        .expect("command failed to start");
+    for arg in args {
+        if arg == "-X" || arg == "-Xhelp" {
+            print_launcher_usage();
+            std::process::exit(0);
+        } else if let Some(vm_opt) = arg.strip_prefix("-J") {
+            config.vm_args.push(vm_opt.to_string());
+        } else {
+            program_args.push(arg);
+        }
+    }
+
+    program_args
+}
+
+fn print_launcher_usage() {
+    msgbox::create(
+        "jGnash Launcher Usage",
+        "-J<option>    Pass <option> directly to the JVM, e.g. -J-Xmx2g\n\
+         -X, -Xhelp    Show this help and exit\n\
+         All other arguments are forwarded to jGnash.",
+        IconType::Info,
+    );
+}
+
+/// If `java_home` is a modular JDK (9+), records a bundled
+/// `modular-jdk.args` file on `config.modular_args_file`. Such JDKs often
+/// need `--add-opens`/`--add-modules` that a classic classpath launch
+/// doesn't supply, so we only do this when it's actually needed and the
+/// file is available. `@argfile` is a `java`-launcher syntax: the spawn
+/// path passes it through as-is, while the in-process JNI path (which
+/// doesn't understand `@argfile`) expands its contents into individual
+/// options via `modular_jdk_args_as_options`.
+fn apply_modular_jdk_args(java_home: &str, config: &mut LaunchConfig) {
+    let args_file = get_execution_path().join("modular-jdk.args");
+
+    if is_modular_jdk(java_home) && args_file.exists() {
+        config.modular_args_file = Some(args_file);
+    }
+}
+
+/// Expands `config.modular_args_file`, if set, into individual VM options
+/// for the JNI Invocation API, which rejects the `@argfile` syntax as an
+/// unrecognized option. The file is whitespace-separated, one flag per
+/// token; quoting and `#` comments, which a `java` launcher argfile
+/// allows, are not handled.
+fn modular_jdk_args_as_options(config: &LaunchConfig) -> Result<Vec<String>, String> {
+    match &config.modular_args_file {
+        Some(path) => {
+            let contents = fs::read_to_string(path)
+                .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+            Ok(contents.split_whitespace().map(|s| s.to_string()).collect())
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Runs `<java_home>/bin/java -version` and reports whether the JDK is
+/// modular (major version 9 or later).
+fn is_modular_jdk(java_home: &str) -> bool {
+    let java_exe = PathBuf::from(java_home)
+        .join("bin")
+        .join(if cfg!(target_family = "windows") {
+            "java.exe"
+        } else {
+            "java"
+        });
+
+    let output = match Command::new(java_exe).arg("-version").output() {
+        Ok(o) => o,
+        Err(_e) => return false,
+    };
+
+    let version_output = String::from_utf8_lossy(&output.stderr);
+    parse_major_version(&version_output).map_or(false, |major| major >= 9)
+}
+
+/// Parses the major version out of a `java -version` report, e.g.
+/// `java version "17.0.1"` -> 17, or the legacy `"1.8.0_292"` -> 8.
+fn parse_major_version(version_output: &str) -> Option<u32> {
+    let start = version_output.find('"')? + 1;
+    let rest = &version_output[start..];
+    let end = rest.find('"')?;
+    let mut parts = rest[..end].split('.');
+
+    let first: u32 = parts.next()?.parse().ok()?;
+    if first == 1 {
+        parts.next()?.parse().ok()
+    } else {
+        Some(first)
+    }
+}
+
+/// Starts jGnash by creating the JVM in-process via the JNI Invocation API,
+/// falling back to spawning a `java`/`javaw` subprocess if that fails.
+fn launch_jgnash(s: String, config: LaunchConfig, program_args: Vec<String>) {
+    if let Err(e) = launch_jgnash_inprocess(&s, &config, &program_args) {
+        msgbox::create(
+            "Warning",
+            &format!(
+                "Could not start the JVM in-process ({}).\n\
+                 Falling back to launching a separate java process.",
+                e
+            ),
+            IconType::Info,
+        );
+        launch_jgnash_spawn(s, &config, &program_args);
+    }
+}
+
+/// Locates the directory containing the `jvm` shared library for the JDK
+/// rooted at `java_home`. Looks
+/// directly under `java_home` rather than deferring to
+/// `java_locator::locate_jvm_dyn_library()`, which re-resolves the
+/// *system* JDK via `JAVA_HOME`/the registry and would ignore a bundled
+/// `runtime/` image or a PATH-discovered home.
+fn locate_jvm_dyn_library_in(java_home: &str) -> Result<String, String> {
+    let lib_name = if cfg!(target_family = "windows") {
+        "jvm.dll"
+    } else if cfg!(target_os = "macos") {
+        "libjvm.dylib"
+    } else {
+        "libjvm.so"
+    };
+
+    for candidate in [
+        "bin/server",
+        "lib/server",
+        "jre/bin/server",
+        "jre/lib/server",
+    ] {
+        let dir = PathBuf::from(java_home).join(candidate);
+
+        if dir.join(lib_name).exists() {
+            return dir
+                .as_os_str()
+                .to_str()
+                .map(|s| s.to_string())
+                .ok_or_else(|| "jvm library path is not valid UTF-8".to_string());
+        }
+    }
+
+    Err(format!("no {} found under {}", lib_name, java_home))
 }
 
-#[cfg(target_family = "unix")]
-fn launch_jgnash(s: String) {
-    let java_exe = s.add("\\bin\\javaw");
+/// Prepends the directory found by `locate_jvm_dyn_library_in` to the
+/// platform's dynamic library search path so the library can be found when
+/// the JVM is created.
+fn add_jvm_lib_to_search_path(java_home: &str) -> Result<(), String> {
+    let jvm_dir = locate_jvm_dyn_library_in(java_home)?;
 
-    let class_path = get_execution_path()
-        .as_os_str()
-        .to_str()
-        .unwrap()
-        .to_string()
-        .add("\\lib\\*");
+    #[cfg(target_family = "windows")]
+    let search_path_var = "PATH";
+    #[cfg(target_os = "macos")]
+    let search_path_var = "DYLD_LIBRARY_PATH";
+    #[cfg(all(target_family = "unix", not(target_os = "macos")))]
+    let search_path_var = "LD_LIBRARY_PATH";
 
-    Command::new(&java_exe)
+    let existing = env::var(search_path_var).unwrap_or_default();
+    let separator = if cfg!(target_family = "windows") {
+        ";"
+    } else {
+        ":"
+    };
+
+    let updated = if existing.is_empty() {
+        jvm_dir
+    } else {
+        format!("{}{}{}", jvm_dir, separator, existing)
+    };
+
+    env::set_var(search_path_var, updated);
+
+    Ok(())
+}
+
+/// Creates the JVM inside this process and invokes `jGnash.main(String[])`
+/// directly, avoiding the need for a `javaw`/`java` wrapper executable and
+/// returning an error message if startup fails.
+fn launch_jgnash_inprocess(
+    java_home: &str,
+    config: &LaunchConfig,
+    program_args: &[String],
+) -> Result<(), String> {
+    add_jvm_lib_to_search_path(java_home)?;
+
+    let class_path = build_classpath(config);
+
+    let mut args_builder = InitArgsBuilder::new()
+        .version(JNIVersion::V8)
+        .option(&format!("-Djava.class.path={}", class_path));
+
+    for vm_arg in &config.vm_args {
+        args_builder = args_builder.option(vm_arg);
+    }
+
+    for module_arg in modular_jdk_args_as_options(config)? {
+        args_builder = args_builder.option(&module_arg);
+    }
+
+    let jvm_args = args_builder
+        .build()
+        .map_err(|e| format!("invalid JVM init args: {}", e))?;
+
+    let jvm = JavaVM::new(jvm_args).map_err(|e| format!("failed to create JVM: {}", e))?;
+
+    let mut env = jvm
+        .attach_current_thread()
+        .map_err(|e| format!("failed to attach current thread: {}", e))?;
+
+    // FindClass expects the internal slash form (e.g. "com/foo/Main"), not
+    // the dotted binary name a user would write in jgnash.json.
+    let jni_class_name = config.main_class.replace('.', "/");
+
+    let main_class = env
+        .find_class(&jni_class_name)
+        .map_err(|e| format!("could not find main class {}: {}", config.main_class, e))?;
+
+    let args = env
+        .new_object_array(
+            program_args.len() as i32,
+            "java/lang/String",
+            jni::objects::JObject::null(),
+        )
+        .map_err(|e| format!("failed to build argv array: {}", e))?;
+
+    for (i, arg) in program_args.iter().enumerate() {
+        let jarg = env
+            .new_string(arg)
+            .map_err(|e| format!("failed to build argv entry: {}", e))?;
+        env.set_object_array_element(&args, i as i32, jarg)
+            .map_err(|e| format!("failed to set argv entry: {}", e))?;
+    }
+
+    env.call_static_method(
+        main_class,
+        "main",
+        "([Ljava/lang/String;)V",
+        &[(&args).into()],
+    )
+    .map_err(|e| format!("jGnash.main() failed: {}", e))?;
+
+    // `jvm` is kept alive for the whole call above so the JVM isn't torn
+    // down before `main` returns.
+    Ok(())
+}
+
+/// Spawns a `javaw`/`java` subprocess as a fallback when the in-process JVM
+/// can't be created. Builds the executable path with `PathBuf::join` so the
+/// platform's own separator and executable suffix are used, rather than
+/// hard-coding one OS's conventions.
+fn launch_jgnash_spawn(s: String, config: &LaunchConfig, program_args: &[String]) {
+    let java_exe_name = if cfg!(target_family = "windows") {
+        "javaw.exe"
+    } else {
+        "java"
+    };
+
+    let java_exe = PathBuf::from(s).join("bin").join(java_exe_name);
+
+    let class_path = build_classpath(config);
+
+    let mut command = Command::new(&java_exe);
+    command.args(&config.vm_args);
+
+    if let Some(args_file) = &config.modular_args_file {
+        command.arg(format!("@{}", args_file.display()));
+    }
+
+    command
         .arg("-classpath")
         .arg(&class_path)
-        .arg("jGnash")
+        .arg(&config.main_class)
+        .args(program_args)
         .spawn()
         .expect("command failed to start");
 }
 
+/// Falls back to searching `PATH` for a `java`/`java.exe` executable,
+/// resolving symlinks to find the real JDK home, when `java_locator`
+/// couldn't find one (e.g. no registry entry / `JAVA_HOME` set).
+fn locate_java_on_path() -> Option<String> {
+    let java_exe_name = if cfg!(target_family = "windows") {
+        "java.exe"
+    } else {
+        "java"
+    };
+
+    let path_var = env::var_os("PATH")?;
+
+    for dir in env::split_paths(&path_var) {
+        let candidate = dir.join(java_exe_name);
+
+        if candidate.is_file() {
+            let resolved = fs::canonicalize(&candidate).unwrap_or(candidate);
+
+            // resolved is <java_home>/bin/<java_exe_name>
+            let java_home = resolved.parent()?.parent()?;
+
+            return java_home.as_os_str().to_str().map(|s| s.to_string());
+        }
+    }
+
+    None
+}
+
+/// Resolves the directory containing this executable, canonicalizing the
+/// path and following symlinks so a relative launch (e.g. from `PATH`)
+/// still finds `lib/`, `runtime/` etc. next to the real binary. Bails out
+/// with an error dialog rather than silently returning an empty path,
+/// which would otherwise build a nonsense classpath.
 fn get_execution_path() -> PathBuf {
-    match env::current_exe() {
+    let resolved = env::current_exe().and_then(fs::canonicalize);
+
+    match resolved {
         Ok(mut path) => {
             path.pop(); // pop off the name of the executable
             path
         }
-        Err(_e) => PathBuf::new(),
+        Err(_e) => {
+            msgbox::create(
+                "Error",
+                "Unable to determine jGnash's install directory.",
+                IconType::Error,
+            );
+            std::process::exit(1);
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_major_version_handles_modern_format() {
+        assert_eq!(
+            parse_major_version("java version \"17.0.1\" 2021-10-19\n"),
+            Some(17)
+        );
+    }
+
+    #[test]
+    fn parse_major_version_handles_legacy_1_dot_x_format() {
+        assert_eq!(
+            parse_major_version("openjdk version \"1.8.0_292\"\n"),
+            Some(8)
+        );
+    }
+
+    #[test]
+    fn parse_major_version_returns_none_when_unparseable() {
+        assert_eq!(parse_major_version("not a version string"), None);
+    }
+
+    #[test]
+    fn parse_argv_strips_j_prefix_into_vm_args() {
+        let mut config = LaunchConfig::default();
+        let program_args = parse_argv(vec!["-J-Xmx2g".to_string()], &mut config);
+
+        assert_eq!(config.vm_args, vec!["-Xmx2g".to_string()]);
+        assert!(program_args.is_empty());
+    }
+
+    #[test]
+    fn parse_argv_forwards_everything_else() {
+        let mut config = LaunchConfig::default();
+        let program_args = parse_argv(
+            vec!["-J-Dfoo=bar".to_string(), "file.xml".to_string()],
+            &mut config,
+        );
+
+        assert_eq!(config.vm_args, vec!["-Dfoo=bar".to_string()]);
+        assert_eq!(program_args, vec!["file.xml".to_string()]);
+    }
+
+    #[test]
+    fn build_classpath_joins_plain_entries_with_platform_separator() {
+        let config = LaunchConfig {
+            classpath: vec!["extra.jar".to_string(), "more.jar".to_string()],
+            ..LaunchConfig::default()
+        };
+
+        let separator = if cfg!(target_family = "windows") {
+            ";"
+        } else {
+            ":"
+        };
+        let class_path = build_classpath_under(&config, Path::new("/opt/jgnash"));
+        let entries: Vec<&str> = class_path.split(separator).collect();
+
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].ends_with(&format!("{}extra.jar", std::path::MAIN_SEPARATOR)));
+        assert!(entries[1].ends_with(&format!("{}more.jar", std::path::MAIN_SEPARATOR)));
+    }
+
+    #[test]
+    fn build_classpath_expands_star_wildcard_into_jar_files() {
+        let base = env::temp_dir().join(format!(
+            "jgnash-launcher-test-{:?}",
+            std::thread::current().id()
+        ));
+        let lib_dir = base.join("lib");
+        fs::create_dir_all(&lib_dir).unwrap();
+        fs::write(lib_dir.join("b.jar"), b"").unwrap();
+        fs::write(lib_dir.join("a.jar"), b"").unwrap();
+        fs::write(lib_dir.join("readme.txt"), b"").unwrap();
+
+        let config = LaunchConfig {
+            classpath: vec!["lib/*".to_string()],
+            ..LaunchConfig::default()
+        };
+
+        let class_path = build_classpath_under(&config, &base);
+        let separator = if cfg!(target_family = "windows") {
+            ";"
+        } else {
+            ":"
+        };
+        let entries: Vec<&str> = class_path.split(separator).collect();
+
+        assert_eq!(entries.len(), 2, "should list jars, not the literal '*'");
+        assert!(entries[0].ends_with("a.jar"));
+        assert!(entries[1].ends_with("b.jar"));
+        assert!(entries.iter().all(|e| !e.ends_with("readme.txt")));
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}